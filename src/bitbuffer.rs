@@ -0,0 +1,258 @@
+//! Bit-packing primitives shared by `BitWriter`/`BitReader`.
+//!
+//! A `BitPackedBuffer` tracks the cursor state common to reading and writing a
+//! densely packed bitstream: `used` is the byte cursor into the underlying
+//! buffer, `next`/`nextbits` hold a partial byte that hasn't been flushed (or
+//! hasn't been fully consumed yet), and `bigendian` picks which end of that
+//! partial byte new bits land in. Values are always taken/placed starting
+//! from their own low bits; `bigendian` only changes which side of the
+//! partial byte those bits occupy.
+
+struct BitPackedBuffer {
+    used: usize,
+    next: u8,
+    nextbits: usize,
+    bigendian: bool,
+}
+
+impl BitPackedBuffer {
+    fn new(bigendian: bool) -> Self {
+        BitPackedBuffer {
+            used: 0,
+            next: 0,
+            nextbits: 0,
+            bigendian,
+        }
+    }
+}
+
+/// Writes values into a growable byte buffer at arbitrary bit widths.
+pub struct BitWriter {
+    state: BitPackedBuffer,
+    bytes: Vec<u8>,
+}
+
+impl BitWriter {
+    pub fn new(bigendian: bool) -> Self {
+        BitWriter {
+            state: BitPackedBuffer::new(bigendian),
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Packs the low `bits` bits of `value`, spilling full bytes into the
+    /// output as they fill.
+    pub fn write_bits(&mut self, mut value: u128, bits: u8) {
+        let mut remaining = bits as usize;
+        while remaining > 0 {
+            let space = 8 - self.state.nextbits;
+            let take = remaining.min(space);
+            let chunk = (value & ((1u128 << take) - 1)) as u8;
+            if self.state.bigendian {
+                self.state.next |= chunk << (space - take);
+            } else {
+                self.state.next |= chunk << self.state.nextbits;
+            }
+            value >>= take;
+            self.state.nextbits += take;
+            remaining -= take;
+
+            if self.state.nextbits == 8 {
+                self.bytes.push(self.state.next);
+                self.state.used += 1;
+                self.state.next = 0;
+                self.state.nextbits = 0;
+            }
+        }
+    }
+
+    /// Flushes any partial byte, padding the remaining bits with zero.
+    pub fn byte_align(&mut self) {
+        if self.state.nextbits > 0 {
+            self.bytes.push(self.state.next);
+            self.state.used += 1;
+            self.state.next = 0;
+            self.state.nextbits = 0;
+        }
+    }
+
+    /// Byte-aligns, then appends `data` verbatim.
+    pub fn write_aligned_bytes(&mut self, data: &[u8]) {
+        self.byte_align();
+        self.bytes.extend_from_slice(data);
+        self.state.used += data.len();
+    }
+
+    /// Consumes the writer, flushing any partial byte and returning the
+    /// packed buffer.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.byte_align();
+        self.bytes
+    }
+}
+
+/// Reads values out of a byte slice at arbitrary bit widths.
+pub struct BitReader<'a> {
+    state: BitPackedBuffer,
+    bytes: &'a [u8],
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8], bigendian: bool) -> Self {
+        BitReader {
+            state: BitPackedBuffer::new(bigendian),
+            bytes,
+        }
+    }
+
+    /// Reads `bits` bits, pulling fresh bytes from the underlying slice as
+    /// needed. Returns `None` if the slice runs out before `bits` bits have
+    /// been read.
+    pub fn read_bits(&mut self, bits: u8) -> Option<u128> {
+        let mut remaining = bits as usize;
+        let mut result: u128 = 0;
+        let mut shift = 0usize;
+
+        while remaining > 0 {
+            if self.state.nextbits == 0 {
+                let byte = *self.bytes.get(self.state.used)?;
+                self.state.next = byte;
+                self.state.nextbits = 8;
+                self.state.used += 1;
+            }
+
+            let take = remaining.min(self.state.nextbits);
+            let chunk = if self.state.bigendian {
+                let c = self.state.next >> (8 - take);
+                if take < 8 {
+                    self.state.next <<= take;
+                } else {
+                    self.state.next = 0;
+                }
+                c
+            } else {
+                let c = self.state.next & ((1u16 << take) - 1) as u8;
+                if take < 8 {
+                    self.state.next >>= take;
+                } else {
+                    self.state.next = 0;
+                }
+                c
+            };
+
+            result |= (chunk as u128) << shift;
+            shift += take;
+            self.state.nextbits -= take;
+            remaining -= take;
+        }
+
+        Some(result)
+    }
+
+    /// Discards any partially-read byte so the next read starts on a byte
+    /// boundary.
+    pub fn byte_align(&mut self) {
+        self.state.next = 0;
+        self.state.nextbits = 0;
+    }
+
+    /// Byte-aligns, then returns the next `n` bytes verbatim.
+    pub fn read_aligned_bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        self.byte_align();
+        let end = self.state.used.checked_add(n)?;
+        let slice = self.bytes.get(self.state.used..end)?;
+        self.state.used = end;
+        Some(slice)
+    }
+}
+
+/// Byte-aligns and writes `value` as a little-endian base-128 varint (the
+/// high bit of each byte marks continuation), so header values don't get
+/// silently truncated to a single byte.
+pub fn write_varint(writer: &mut BitWriter, mut value: u32) {
+    loop {
+        let mut byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        writer.write_aligned_bytes(&[byte]);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// Byte-aligns and reads a varint written by [`write_varint`].
+pub fn read_varint(reader: &mut BitReader) -> Option<u32> {
+    let mut value: u32 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = reader.read_aligned_bytes(1)?[0];
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bigendian: bool, widths: &[u8], values: &[u128]) {
+        let mut writer = BitWriter::new(bigendian);
+        for (&width, &value) in widths.iter().zip(values) {
+            writer.write_bits(value, width);
+        }
+        let bytes = writer.finish();
+
+        let mut reader = BitReader::new(&bytes, bigendian);
+        for (&width, &value) in widths.iter().zip(values) {
+            assert_eq!(reader.read_bits(width).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn round_trips_byte_spanning_widths_little_endian() {
+        round_trip(false, &[1, 8, 14, 32], &[1, 0xAB, 0x1FFF, 0xDEAD_BEEF]);
+    }
+
+    #[test]
+    fn round_trips_byte_spanning_widths_big_endian() {
+        round_trip(true, &[1, 8, 14, 32], &[1, 0xAB, 0x1FFF, 0xDEAD_BEEF]);
+    }
+
+    #[test]
+    fn reads_fresh_byte_without_overflow() {
+        // A single 8-bit read consumes a whole fresh byte in one step,
+        // which previously overflowed the `next` shift.
+        for bigendian in [false, true] {
+            let mut writer = BitWriter::new(bigendian);
+            writer.write_bits(0xFF, 8);
+            let bytes = writer.finish();
+            let mut reader = BitReader::new(&bytes, bigendian);
+            assert_eq!(reader.read_bits(8), Some(0xFF));
+        }
+    }
+
+    #[test]
+    fn read_bits_returns_none_past_end_of_buffer() {
+        let mut reader = BitReader::new(&[0u8; 1], false);
+        assert_eq!(reader.read_bits(1), Some(0));
+        assert_eq!(reader.read_bits(8), None);
+    }
+
+    #[test]
+    fn varint_round_trips_values_beyond_a_single_byte() {
+        for value in [0u32, 1, 127, 128, 300, u32::MAX] {
+            let mut writer = BitWriter::new(false);
+            write_varint(&mut writer, value);
+            let bytes = writer.finish();
+            let mut reader = BitReader::new(&bytes, false);
+            assert_eq!(read_varint(&mut reader), Some(value));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Error type returned by the fallible `Schema::encode`/`decode` API.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum SerError<'a> {
+    /// The input map had no entry for this field.
+    MissingField(&'a str),
+    /// The input map had an entry for this field, but it was the wrong
+    /// `Value` variant.
+    TypeMismatch { field: &'a str, expected: &'static str },
+    /// An `Int` field's value fell outside its declared `[min, max]` range.
+    OutOfRange { field: &'a str, value: i32, min: i32, max: i32 },
+    /// A `Float` field's value fell outside its declared `[min, max]` range.
+    OutOfRangeFloat { field: &'a str, value: f64, min: f64, max: f64 },
+    /// The buffer ended before all fields could be read.
+    BufferTruncated,
+    /// A bytes field's declared length exceeds its schema maximum.
+    ByteCountExceeded { field: &'a str, length: usize, max: i32 },
+}
+
+impl<'a> fmt::Display for SerError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerError::MissingField(field) => write!(f, "missing field `{field}`"),
+            SerError::TypeMismatch { field, expected } => {
+                write!(f, "field `{field}` expected a {expected} value")
+            }
+            SerError::OutOfRange { field, value, min, max } => write!(
+                f,
+                "field `{field}` value {value} out of range [{min}, {max}]"
+            ),
+            SerError::OutOfRangeFloat { field, value, min, max } => write!(
+                f,
+                "field `{field}` value {value} out of range [{min}, {max}]"
+            ),
+            SerError::BufferTruncated => write!(f, "buffer truncated"),
+            SerError::ByteCountExceeded { field, length, max } => write!(
+                f,
+                "field `{field}` has length {length}, exceeding max {max}"
+            ),
+        }
+    }
+}
+
+impl<'a> std::error::Error for SerError<'a> {}
@@ -1,5 +1,11 @@
 use std::collections::hash_map::HashMap;
 
+mod bitbuffer;
+mod error;
+
+use bitbuffer::{read_varint, write_varint, BitReader, BitWriter};
+use error::SerError;
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 struct IntField<'a> {
     name:Option< &'a str>,
@@ -7,53 +13,131 @@ struct IntField<'a> {
     max: Option<i32>,
     bits:Option<i32>,
     always_present: Option<bool>,
-}   
-    
+    optional: Option<bool>,
+    /// Schema version this field was introduced in. `None` means version 0.
+    introduced_in: Option<u32>,
+    /// Value to fill in when decoding a buffer older than `introduced_in`.
+    /// Falls back to `min` when unset.
+    default: Option<i32>,
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 struct BooleanField<'a> {
     name: Option<&'a str>,
     bits: Option<i32>,
+    optional: Option<bool>,
+    introduced_in: Option<u32>,
+    default: Option<bool>,
 }
-    
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 struct BytesField<'a> {
     name:Option< &'a str>,
     max: Option<i32>,
     bits:Option<i32>,
+    optional: Option<bool>,
+    introduced_in: Option<u32>,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(PartialEq, PartialOrd, Debug)]
+struct FloatField<'a> {
+    name: Option<&'a str>,
+    min: Option<f64>,
+    max: Option<f64>,
+    /// Number of binary fraction bits to keep when quantizing.
+    precision: Option<u32>,
+    bits: Option<i32>,
+    optional: Option<bool>,
+    introduced_in: Option<u32>,
+    default: Option<f64>,
+}
+
+#[derive(PartialEq, PartialOrd, Debug)]
 enum Field<'a> {
     Int(IntField<'a>),
     Boolean(BooleanField<'a>),
     Bytes(BytesField<'a>),
+    Float(FloatField<'a>),
 }
 
-#[derive(Debug)]
+impl<'a> Field<'a> {
+    fn name(&self) -> &'a str {
+        match self {
+            Field::Int(f) => f.name.unwrap(),
+            Field::Boolean(f) => f.name.unwrap(),
+            Field::Bytes(f) => f.name.unwrap(),
+            Field::Float(f) => f.name.unwrap(),
+        }
+    }
+
+    fn bits(&self) -> i32 {
+        match self {
+            Field::Int(f) => f.bits.unwrap(),
+            Field::Boolean(f) => f.bits.unwrap(),
+            Field::Bytes(f) => f.bits.unwrap(),
+            Field::Float(f) => f.bits.unwrap(),
+        }
+    }
+
+    fn is_optional(&self) -> bool {
+        match self {
+            Field::Int(f) => f.optional.unwrap_or(false),
+            Field::Boolean(f) => f.optional.unwrap_or(false),
+            Field::Bytes(f) => f.optional.unwrap_or(false),
+            Field::Float(f) => f.optional.unwrap_or(false),
+        }
+    }
+
+    fn introduced_in(&self) -> u32 {
+        match self {
+            Field::Int(f) => f.introduced_in.unwrap_or(0),
+            Field::Boolean(f) => f.introduced_in.unwrap_or(0),
+            Field::Bytes(f) => f.introduced_in.unwrap_or(0),
+            Field::Float(f) => f.introduced_in.unwrap_or(0),
+        }
+    }
+
+    /// Value substituted for this field when decoding a buffer from a
+    /// schema version that predates it.
+    fn default_value(&self) -> Value<'a> {
+        match self {
+            Field::Int(f) => Value::Int(f.default.unwrap_or_else(|| f.min.unwrap())),
+            Field::Boolean(f) => Value::Boolean(f.default.unwrap_or(false)),
+            Field::Bytes(_) => Value::Buffer(&[]),
+            Field::Float(f) => Value::Float(f.default.unwrap_or_else(|| f.min.unwrap())),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
 enum Value<'a> {
     Buffer(&'a [u8]),
     Int(i32),
     Boolean(bool),
+    Float(f64),
 }
 
 #[allow(unused)]
 #[derive(Debug)]
 struct Schema<'a> {
     fields: Vec<Field<'a>>,
-    int: i32,
     max_byte_length: i32,
-    bytes: Vec<i32>,
+    /// Current schema version. Written as a varint header by `encode` so
+    /// `decode_versioned` can interoperate with older buffers.
+    version: u32,
 }
 
 impl<'a> Schema<'a> {
-    fn construct(fields: &'a mut [Field<'a>]) -> Self {
+    fn construct(fields: &'a mut [Field<'a>], version: u32) -> Self {
         let mut max_bits = 0;
         let mut v = Vec::<Field>::new();
-        for i in 0..fields.len() {
-            if let Field::Int(IntField {min, max, name, ..}) = fields[i] {
+        let mut n_optional = 0;
+        for field in fields.iter() {
+            if let Field::Int(IntField {min, max, name, optional, introduced_in, default, ..}) = *field {
                 let normalized = max.unwrap() - min.unwrap();
                 let bits = (f32::log2((normalized + 1) as f32)).ceil() as i32;
                 max_bits += bits;
+                if optional.unwrap_or(false) { n_optional += 1; }
                 v.push(Field::Int(
                     IntField {
                         name,
@@ -61,28 +145,60 @@ impl<'a> Schema<'a> {
                         bits: Some(bits),
                         max,
                         always_present: Some(bits == 0),
-                    }       
-                )); 
-            } else if let Field::Boolean(BooleanField { name, .. }) = fields[i] {
-                max_bits = max_bits + 1;
+                        optional,
+                        introduced_in,
+                        default,
+                    }
+                ));
+            } else if let Field::Boolean(BooleanField { name, optional, introduced_in, default, .. }) = *field {
+                max_bits += 1;
+                if optional.unwrap_or(false) { n_optional += 1; }
                 v.push(Field::Boolean(
-                    BooleanField {  
+                    BooleanField {
                         bits: Some(1),
                         name,
-                    }   
+                        optional,
+                        introduced_in,
+                        default,
+                    }
                 ));
-            } else if let Field::Bytes(BytesField {name, max, ..}) = fields[i] {
+            } else if let Field::Bytes(BytesField {name, max, optional, introduced_in, ..}) = *field {
                 let bits = (f32::log2((max.unwrap() + 1) as f32)).ceil() as i32;
-                max_bits += bits;   
+                max_bits += bits;
+                if optional.unwrap_or(false) { n_optional += 1; }
                 v.push(Field::Bytes(
-                    BytesField {  
+                    BytesField {
                         bits: Some(bits),
                         name,
                         max,
+                        optional,
+                        introduced_in,
                     }
                 ));
-            }   
+            } else if let Field::Float(FloatField {name, min, max, precision, optional, introduced_in, default, ..}) = *field {
+                // Capped well below 64: `1u64 << precision` would otherwise
+                // panic, and no real fixed-point field needs this many
+                // fraction bits.
+                let precision = precision.unwrap_or(0).min(52);
+                let steps = (max.unwrap() - min.unwrap()) * (1u64 << precision) as f64;
+                let bits = (f64::log2(steps + 1.0)).ceil() as i32;
+                max_bits += bits;
+                if optional.unwrap_or(false) { n_optional += 1; }
+                v.push(Field::Float(
+                    FloatField {
+                        bits: Some(bits),
+                        name,
+                        min,
+                        max,
+                        precision: Some(precision),
+                        optional,
+                        introduced_in,
+                        default,
+                    }
+                ));
+            }
         }
+        max_bits += n_optional;
 
         v.sort_by(|a, b| {
             if let Field::Bytes(_) = a {
@@ -92,143 +208,218 @@ impl<'a> Schema<'a> {
             } else {
                 std::cmp::Ordering::Equal
             }
-        }); 
+        });
 
         let max_byte_length = ((max_bits / 8) as f32).ceil() as i32;
 
-        Schema {    
+        Schema {
             fields: v,
-            int: 0,
             max_byte_length,
-            bytes: Vec::<i32>::new(),
+            version,
         }
     }
 
-    fn to_buffer(&self, value: HashMap<&'a str, Value>) -> Vec<u8> {
-        let mut int = 0;
-        let mut written_bytes = 0_i32;
-        let mut bytes =  Vec::new();
+    /// Fallible encode: validates each value against its field's declared
+    /// range/length before packing that field's bits, and returns the first
+    /// error found.
+    fn encode(&self, value: &HashMap<&'a str, Value<'a>>) -> Result<Vec<u8>, SerError<'a>> {
+        let mut writer = BitWriter::new(false);
         let mut buffers = Vec::<&[u8]>::new();
-        let mut total_buffers_length = 0;
-
-        for i in 0..self.fields.len() {
-            if let Field::Int(IntField {name, min, bits, ..}) = self.fields[i] {
-                if let Value::Int(val) = value.get(name.unwrap()).unwrap() {
-                    let normalized = val - min.unwrap();
-                    int |= normalized << written_bytes;
-                    if let Some(v) = bits { written_bytes += v; }
-                }       
-            } else if let Field::Boolean(BooleanField { name,.. }) = self.fields[i] {
-                if let Value::Boolean(expr) = value.get(name.unwrap()).unwrap() { 
-                    int |= (if *expr { 1 } else  { 0 }) << written_bytes;      
-                    written_bytes = written_bytes + 1;
-                };      
-            } else if let Field::Bytes(BytesField { name, bits, .. }) = self.fields[i] {
-                if let Value::Buffer(buffer) = value.get(name.unwrap()).unwrap() {
-                    buffers.push(*buffer);          
-                    total_buffers_length = total_buffers_length + buffer.len();
-                    int |= (buffer.len() as i32) << written_bytes;
-                    written_bytes += bits.unwrap();
-                }
+
+        // Leading schema-version header, read back by `decode_versioned`.
+        // Written as a varint so versions beyond 255 aren't truncated.
+        write_varint(&mut writer, self.version);
+
+        // Leading presence bitmap: one bit per optional field, in field order.
+        // Packed tightly (no byte padding between it and the first field's
+        // bits), rather than reserved as a whole number of bytes.
+        for field in &self.fields {
+            if field.is_optional() {
+                let present = value.contains_key(field.name());
+                writer.write_bits(if present { 1 } else { 0 }, 1);
             }
+        }
 
-            while written_bytes >= 8 && int > 0 {
-                bytes.push(int & 0b11111111);
-                int >>= 8;
-                written_bytes -= 8;
+        for field in &self.fields {
+            let name = field.name();
+            if field.is_optional() && !value.contains_key(name) {
+                continue;
             }
-        }   
 
-        while written_bytes > 0 && int > 0 {
-            bytes.push(int & 0b11111111);
-            int >>= 8;
-            written_bytes -= 8;
+            match field {
+                Field::Int(IntField { min, max, bits, .. }) => {
+                    let val = match value.get(name) {
+                        Some(Value::Int(v)) => *v,
+                        Some(_) => {
+                            return Err(SerError::TypeMismatch { field: name, expected: "Int" })
+                        }
+                        None => return Err(SerError::MissingField(name)),
+                    };
+                    let (min, max) = (min.unwrap(), max.unwrap());
+                    if val < min || val > max {
+                        return Err(SerError::OutOfRange { field: name, value: val, min, max });
+                    }
+                    writer.write_bits((val - min) as u128, bits.unwrap() as u8);
+                }
+                Field::Boolean(_) => {
+                    let expr = match value.get(name) {
+                        Some(Value::Boolean(b)) => *b,
+                        Some(_) => {
+                            return Err(SerError::TypeMismatch { field: name, expected: "Boolean" })
+                        }
+                        None => return Err(SerError::MissingField(name)),
+                    };
+                    writer.write_bits(if expr { 1 } else { 0 }, 1);
+                }
+                Field::Bytes(BytesField { max, bits, .. }) => {
+                    let buffer = match value.get(name) {
+                        Some(Value::Buffer(b)) => *b,
+                        Some(_) => {
+                            return Err(SerError::TypeMismatch { field: name, expected: "Buffer" })
+                        }
+                        None => return Err(SerError::MissingField(name)),
+                    };
+                    let max = max.unwrap();
+                    if buffer.len() as i32 > max {
+                        return Err(SerError::ByteCountExceeded {
+                            field: name,
+                            length: buffer.len(),
+                            max,
+                        });
+                    }
+                    writer.write_bits(buffer.len() as u128, bits.unwrap() as u8);
+                    buffers.push(buffer);
+                }
+                Field::Float(FloatField { min, max, precision, bits, .. }) => {
+                    let val = match value.get(name) {
+                        Some(Value::Float(v)) => *v,
+                        Some(_) => {
+                            return Err(SerError::TypeMismatch { field: name, expected: "Float" })
+                        }
+                        None => return Err(SerError::MissingField(name)),
+                    };
+                    let (min, max) = (min.unwrap(), max.unwrap());
+                    if val < min || val > max {
+                        return Err(SerError::OutOfRangeFloat { field: name, value: val, min, max });
+                    }
+                    let scale = (1u64 << precision.unwrap()) as f64;
+                    let quantized = ((val - min) * scale).round() as u128;
+                    writer.write_bits(quantized, bits.unwrap() as u8);
+                }
+            }
         }
 
-        let mut byte_array = Vec::<u8>::with_capacity(bytes.len() + total_buffers_length);  
-        for byte in &bytes {
-            byte_array.push(*byte as u8);
+        for buffer in buffers {
+            writer.write_aligned_bytes(buffer);
         }
 
-        for i in 0..buffers.len() {
-            let buffer = buffers[i];
-            for j in 0..buffer.len() {
-                byte_array.insert(bytes.len() + j, buffer[j]);
-            }
-        }
+        Ok(writer.finish())
+    }
 
-        byte_array
+    /// Fallible decode: bounds-checks the buffer before every read instead
+    /// of indexing blindly. Thin wrapper over [`Schema::decode_versioned`].
+    fn decode(&'a self, buffer: &'a [u8]) -> Result<HashMap<&'a str, Value<'a>>, SerError<'a>> {
+        self.decode_versioned(buffer)
     }
-    
-    fn from_buffer(&'a self, buffer: &'a Vec<u8>) -> HashMap<&'a str, Value> {
-        let fields = &self.fields;
 
-        let mut int = buffer[0] as i32;
-        let mut read_bits = 8;
-        let mut buffer_index = 1;
+    /// Decodes a buffer written by a schema at or below this schema's
+    /// version. Reads the leading version header, then only consumes bits
+    /// for fields that existed at that version; fields added in later
+    /// versions are filled with their declared defaults instead. Field
+    /// bit-widths are frozen per version, so appending fields never shifts
+    /// the offsets of earlier ones.
+    fn decode_versioned(&'a self, buffer: &'a [u8]) -> Result<HashMap<&'a str, Value<'a>>, SerError<'a>> {
+        let mut reader = BitReader::new(buffer, false);
+        let version = read_varint(&mut reader).ok_or(SerError::BufferTruncated)?;
 
         let mut value: HashMap<&str, Value> = HashMap::new();
+        let mut pending_bytes_fields = Vec::new();
 
-        for i in 0..fields.len() {
-            let field = &fields[i];
-            if let Field::Int(IntField { always_present,name, min, .. }) = field {
-                if let Some(present) = always_present {
-                    if *present {
-                        value.insert(name.unwrap(), Value::Int(min.unwrap()));  
-                        continue;
-                    }
+        let mut present = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            if field.introduced_in() > version {
+                present.push(false);
+                continue;
+            }
+            present.push(if field.is_optional() {
+                reader.read_bits(1).ok_or(SerError::BufferTruncated)? == 1
+            } else {
+                true
+            });
+        }
+
+        for (field, &is_present) in self.fields.iter().zip(present.iter()) {
+            let name = field.name();
+
+            if field.introduced_in() > version {
+                value.insert(name, field.default_value());
+                continue;
+            }
+            if !is_present {
+                continue;
+            }
+
+            if let Field::Int(IntField { always_present: Some(true), min, .. }) = field {
+                value.insert(name, Value::Int(min.unwrap()));
+                continue;
+            }
+
+            let bits_read = reader.read_bits(field.bits() as u8).ok_or(SerError::BufferTruncated)?;
+
+            match field {
+                Field::Int(IntField { min, .. }) => {
+                    value.insert(name, Value::Int(bits_read as i32 + min.unwrap()));
+                }
+                Field::Boolean(_) => {
+                    value.insert(name, Value::Boolean(bits_read == 1));
+                }
+                Field::Bytes(_) => {
+                    pending_bytes_fields.push((name, bits_read as usize));
+                }
+                Field::Float(FloatField { min, precision, .. }) => {
+                    let scale = (1u64 << precision.unwrap()) as f64;
+                    value.insert(name, Value::Float(bits_read as f64 / scale + min.unwrap()));
                 }
-            }  
-
-            let bits = match field {
-                Field::Int(f) => f.bits.unwrap(),
-                Field::Bytes(f) => f.bits.unwrap(),
-                Field::Boolean(f) => f.bits.unwrap(),
-            };
-
-            while read_bits < bits {
-                int |= (buffer[buffer_index] as i32) << read_bits;
-                buffer_index += 1;
-                read_bits += 8;
-            }   
-
-            if let Field::Int(IntField { name, min, .. }) = field {
-                let mask = (1 << bits) - 1;
-                let val = int & mask;
-                value.insert(name.unwrap(), Value::Int(val + min.unwrap()));
-                int >>= bits;
-                read_bits -= bits;
-            } else if let Field::Boolean(BooleanField { name, .. }) = field {
-                value.insert(name.unwrap(), Value::Boolean((int & 1) == 1));
-                int >>= 1;
-                read_bits -= 1;     
-            } else if let Field::Bytes(BytesField { name, .. }) = field {
-                let mask =  (1 << bits) - 1;  
-                let length = (int & mask) as usize;
-                int >>= bits;
-                read_bits -= bits;
-                value.insert(name.unwrap(), Value::Buffer(&buffer[buffer.len() - length..buffer.len()]));
             }
         }
 
-        value       
+        for (name, length) in pending_bytes_fields {
+            let bytes = reader
+                .read_aligned_bytes(length)
+                .ok_or(SerError::BufferTruncated)?;
+            value.insert(name, Value::Buffer(bytes));
+        }
+
+        Ok(value)
+    }
+
+    /// Panicking convenience wrapper around [`Schema::encode`].
+    fn to_buffer(&self, value: HashMap<&'a str, Value<'a>>) -> Vec<u8> {
+        self.encode(&value).unwrap()
+    }
+
+    /// Panicking convenience wrapper around [`Schema::decode`].
+    #[allow(clippy::wrong_self_convention)]
+    fn from_buffer(&'a self, buffer: &'a [u8]) -> HashMap<&'a str, Value<'a>> {
+        self.decode(buffer).unwrap()
     }
 }
 
 fn main() {
     let fields = &mut [
-        Field::Int(IntField { name: Some("language"), min: Some(0), max: Some(0), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("gameMode"), min: Some(0), max: Some(0), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("regenChallengeDifficulty"), min: Some(0), max: Some(2), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("regenChallenges"), min: Some(1), max: Some(3), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("solvesPerSyllable"), min: Some(-5000), max: Some(5000), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("turnDuration"), min: Some(1), max: Some(10), bits: None, always_present: None }),
-        Field::Int(IntField { name: Some("startingLives"), min: Some(1), max: Some(5), bits: None, always_present: None }),
-	Field::Int(IntField { name: Some("maxLives"), min: Some(1), max: Some(5), bits: None, always_present: None }),
-	Field::Int(IntField { name: Some("syllableDuration"), min: Some(1), max: Some(10), bits: None, always_present: None }),
-	Field::Boolean(BooleanField { name: Some("allowHyphensAndApostrophesInSyllables"), bits: None }),
-	Field::Bytes(BytesField { name: Some("buffer"), max: Some(1000), bits: None }),
-    ];  
+        Field::Int(IntField { name: Some("language"), min: Some(0), max: Some(0), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("gameMode"), min: Some(0), max: Some(0), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("regenChallengeDifficulty"), min: Some(0), max: Some(2), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("regenChallenges"), min: Some(1), max: Some(3), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("solvesPerSyllable"), min: Some(-5000), max: Some(5000), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("turnDuration"), min: Some(1), max: Some(10), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+        Field::Int(IntField { name: Some("startingLives"), min: Some(1), max: Some(5), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+	Field::Int(IntField { name: Some("maxLives"), min: Some(1), max: Some(5), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+	Field::Int(IntField { name: Some("syllableDuration"), min: Some(1), max: Some(10), bits: None, always_present: None, optional: None, introduced_in: None, default: None }),
+	Field::Boolean(BooleanField { name: Some("allowHyphensAndApostrophesInSyllables"), bits: None, optional: None, introduced_in: None, default: None }),
+	Field::Float(FloatField { name: Some("difficultyMultiplier"), min: Some(0.5), max: Some(2.0), precision: Some(4), bits: None, optional: None, introduced_in: None, default: None }),
+	Field::Bytes(BytesField { name: Some("buffer"), max: Some(1000), bits: None, optional: Some(true), introduced_in: None }),
+    ];
 
     let mut hashmap: HashMap<&str, Value> = HashMap::new();
     hashmap.insert("language", Value::Int(0));
@@ -241,11 +432,227 @@ fn main() {
     hashmap.insert("maxLives", Value::Int(3));
     hashmap.insert("syllableDuration", Value::Int(2));
     hashmap.insert("allowHyphensAndApostrophesInSyllables", Value::Boolean(false));
+    hashmap.insert("difficultyMultiplier", Value::Float(1.5));
     hashmap.insert("buffer", Value::Buffer("hello world".as_bytes()));
 
-    let bytes = Schema::construct(fields);
+    let bytes = Schema::construct(fields, 0);
     let encoded = bytes.to_buffer(hashmap);
     println!("{:?}", encoded);
-    let decoded = bytes.from_buffer(&encoded); 
+    let decoded = bytes.from_buffer(&encoded);
     println!("{:#?}", decoded);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_int_schema<'a>(fields: &'a mut [Field<'a>]) -> Schema<'a> {
+        Schema::construct(fields, 0)
+    }
+
+    #[test]
+    fn encode_reports_missing_field() {
+        let fields = &mut [Field::Int(IntField {
+            name: Some("a"),
+            min: Some(0),
+            max: Some(10),
+            bits: None,
+            always_present: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let schema = single_int_schema(fields);
+        let err = schema.encode(&HashMap::new()).unwrap_err();
+        assert_eq!(err, SerError::MissingField("a"));
+    }
+
+    #[test]
+    fn encode_reports_type_mismatch() {
+        let fields = &mut [Field::Int(IntField {
+            name: Some("a"),
+            min: Some(0),
+            max: Some(10),
+            bits: None,
+            always_present: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let schema = single_int_schema(fields);
+        let mut value = HashMap::new();
+        value.insert("a", Value::Boolean(true));
+        let err = schema.encode(&value).unwrap_err();
+        assert_eq!(err, SerError::TypeMismatch { field: "a", expected: "Int" });
+    }
+
+    #[test]
+    fn encode_reports_out_of_range() {
+        let fields = &mut [Field::Int(IntField {
+            name: Some("a"),
+            min: Some(0),
+            max: Some(10),
+            bits: None,
+            always_present: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let schema = single_int_schema(fields);
+        let mut value = HashMap::new();
+        value.insert("a", Value::Int(100));
+        let err = schema.encode(&value).unwrap_err();
+        assert_eq!(err, SerError::OutOfRange { field: "a", value: 100, min: 0, max: 10 });
+    }
+
+    #[test]
+    fn encode_reports_byte_count_exceeded() {
+        let fields = &mut [Field::Bytes(BytesField {
+            name: Some("buf"),
+            max: Some(2),
+            bits: None,
+            optional: None,
+            introduced_in: None,
+        })];
+        let schema = single_int_schema(fields);
+        let mut value = HashMap::new();
+        value.insert("buf", Value::Buffer(b"too long"));
+        let err = schema.encode(&value).unwrap_err();
+        assert_eq!(err, SerError::ByteCountExceeded { field: "buf", length: 8, max: 2 });
+    }
+
+    #[test]
+    fn decode_reports_buffer_truncated() {
+        let fields = &mut [Field::Int(IntField {
+            name: Some("a"),
+            min: Some(0),
+            max: Some(10),
+            bits: None,
+            always_present: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let schema = single_int_schema(fields);
+        let err = schema.decode(&[]).unwrap_err();
+        assert_eq!(err, SerError::BufferTruncated);
+    }
+
+    #[test]
+    fn optional_field_round_trips_present_and_absent() {
+        let fields = &mut [
+            Field::Int(IntField {
+                name: Some("a"),
+                min: Some(0),
+                max: Some(10),
+                bits: None,
+                always_present: None,
+                optional: None,
+                introduced_in: None,
+                default: None,
+            }),
+            Field::Bytes(BytesField {
+                name: Some("buf"),
+                max: Some(10),
+                bits: None,
+                optional: Some(true),
+                introduced_in: None,
+            }),
+        ];
+        let schema = Schema::construct(fields, 0);
+
+        let mut present = HashMap::new();
+        present.insert("a", Value::Int(3));
+        present.insert("buf", Value::Buffer(b"hi"));
+        let encoded = schema.encode(&present).unwrap();
+        let decoded = schema.decode(&encoded).unwrap();
+        match decoded.get("buf") {
+            Some(Value::Buffer(b)) => assert_eq!(*b, b"hi"),
+            other => panic!("expected Value::Buffer(\"hi\"), got {other:?}"),
+        }
+
+        let mut absent = HashMap::new();
+        absent.insert("a", Value::Int(3));
+        let encoded = schema.encode(&absent).unwrap();
+        let decoded = schema.decode(&encoded).unwrap();
+        assert!(!decoded.contains_key("buf"));
+    }
+
+    #[test]
+    fn float_round_trips_within_quantization_tolerance() {
+        let fields = &mut [Field::Float(FloatField {
+            name: Some("x"),
+            min: Some(0.5),
+            max: Some(2.0),
+            precision: Some(4),
+            bits: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let schema = Schema::construct(fields, 0);
+
+        let mut value = HashMap::new();
+        value.insert("x", Value::Float(1.5));
+        let encoded = schema.encode(&value).unwrap();
+        let decoded = schema.decode(&encoded).unwrap();
+
+        match decoded.get("x") {
+            Some(Value::Float(v)) => assert!((v - 1.5).abs() <= 1.0 / 16.0),
+            other => panic!("expected Value::Float, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_versioned_backfills_default_for_field_added_later() {
+        let old_fields = &mut [Field::Int(IntField {
+            name: Some("a"),
+            min: Some(0),
+            max: Some(10),
+            bits: None,
+            always_present: None,
+            optional: None,
+            introduced_in: None,
+            default: None,
+        })];
+        let old_schema = Schema::construct(old_fields, 0);
+
+        let mut old_value = HashMap::new();
+        old_value.insert("a", Value::Int(3));
+        let old_buffer = old_schema.encode(&old_value).unwrap();
+
+        let new_fields = &mut [
+            Field::Int(IntField {
+                name: Some("a"),
+                min: Some(0),
+                max: Some(10),
+                bits: None,
+                always_present: None,
+                optional: None,
+                introduced_in: None,
+                default: None,
+            }),
+            Field::Int(IntField {
+                name: Some("b"),
+                min: Some(0),
+                max: Some(10),
+                bits: None,
+                always_present: None,
+                optional: None,
+                introduced_in: Some(1),
+                default: Some(7),
+            }),
+        ];
+        let new_schema = Schema::construct(new_fields, 1);
+
+        let decoded = new_schema.decode_versioned(&old_buffer).unwrap();
+        match decoded.get("a") {
+            Some(Value::Int(v)) => assert_eq!(*v, 3),
+            other => panic!("expected Value::Int(3), got {other:?}"),
+        }
+        match decoded.get("b") {
+            Some(Value::Int(v)) => assert_eq!(*v, 7),
+            other => panic!("expected Value::Int(7) default, got {other:?}"),
+        }
+    }
+}